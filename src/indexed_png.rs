@@ -0,0 +1,75 @@
+//! Writes quantized output as an indexed (palette) PNG instead of full RGB8,
+//! by driving the `png` encoder directly rather than going through
+//! `image::save_buffer_with_format`'s RGB8 path.
+
+use crate::TransferError;
+use png::{BitDepth, ColorType, Encoder};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Writes `indices` (one palette index per pixel, row-major) as an indexed
+/// PNG, embedding `palette` as the PLTE chunk. Picks the bit depth from the
+/// palette size: 1-bit for <=2 colors, 4-bit for <=16, 8-bit otherwise (2-bit
+/// is never used); `palette` must have at most 256 entries.
+pub fn write_indexed_png(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    indices: &[u8],
+    palette: &[[u8; 3]],
+) -> Result<(), TransferError> {
+    if palette.len() > 256 {
+        return Err(TransferError::PaletteTooLargeError);
+    }
+
+    let file = File::create(path).map_err(TransferError::IoError)?;
+    let writer = BufWriter::new(file);
+
+    let bit_depth = if palette.len() <= 2 {
+        BitDepth::One
+    } else if palette.len() <= 16 {
+        BitDepth::Four
+    } else {
+        BitDepth::Eight
+    };
+
+    let mut encoder = Encoder::new(writer, width, height);
+    encoder.set_color(ColorType::Indexed);
+    encoder.set_depth(bit_depth);
+    encoder.set_palette(palette.iter().flatten().copied().collect::<Vec<u8>>());
+
+    let mut writer = encoder.write_header().map_err(TransferError::PngError)?;
+    writer
+        .write_image_data(&pack_rows(indices, width as usize, bit_depth))
+        .map_err(TransferError::PngError)?;
+    Ok(())
+}
+
+/// Packs one-index-per-byte `indices` down into `bit_depth`-wide samples,
+/// MSB first, padding the end of each row out to a whole byte as the PNG
+/// spec requires for sub-byte bit depths.
+fn pack_rows(indices: &[u8], width: usize, bit_depth: BitDepth) -> Vec<u8> {
+    let bits = match bit_depth {
+        BitDepth::One => 1,
+        BitDepth::Two => 2,
+        BitDepth::Four => 4,
+        BitDepth::Eight => return indices.to_vec(),
+        BitDepth::Sixteen => return indices.to_vec(),
+    };
+
+    let samples_per_byte = 8 / bits;
+    let row_bytes = (width + samples_per_byte - 1) / samples_per_byte;
+    let mut packed = Vec::with_capacity(row_bytes * indices.len() / width.max(1));
+
+    for row in indices.chunks(width) {
+        let mut row_packed = vec![0u8; row_bytes];
+        for (i, &index) in row.iter().enumerate() {
+            let shift = 8 - bits * ((i % samples_per_byte) + 1);
+            row_packed[i / samples_per_byte] |= index << shift;
+        }
+        packed.extend_from_slice(&row_packed);
+    }
+
+    packed
+}
@@ -0,0 +1,98 @@
+//! A tiny 3D k-d tree used to find the nearest palette color in `MatchMode::Quantize`.
+
+struct Node {
+    point: [f32; 3],
+    index: usize,
+    axis: usize,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+}
+
+pub(crate) struct KdTree {
+    root: Option<Box<Node>>,
+}
+
+impl KdTree {
+    /// Builds a balanced k-d tree over `points`, splitting each node on the axis
+    /// of largest spread among the points it covers.
+    pub(crate) fn build(points: &[[f32; 3]]) -> KdTree {
+        let mut indices: Vec<usize> = (0..points.len()).collect();
+        KdTree {
+            root: Self::build_node(points, &mut indices),
+        }
+    }
+
+    fn build_node(points: &[[f32; 3]], indices: &mut [usize]) -> Option<Box<Node>> {
+        if indices.is_empty() {
+            return None;
+        }
+        let axis = Self::widest_axis(points, indices);
+        indices.sort_unstable_by(|&a, &b| points[a][axis].partial_cmp(&points[b][axis]).unwrap());
+
+        let mid = indices.len() / 2;
+        let (left_indices, rest) = indices.split_at_mut(mid);
+        let (median, right_indices) = rest.split_first_mut().unwrap();
+
+        Some(Box::new(Node {
+            point: points[*median],
+            index: *median,
+            axis,
+            left: Self::build_node(points, left_indices),
+            right: Self::build_node(points, right_indices),
+        }))
+    }
+
+    /// Returns the axis (0=x, 1=y, 2=z) along which `indices` spread the most.
+    fn widest_axis(points: &[[f32; 3]], indices: &[usize]) -> usize {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for &i in indices {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(points[i][axis]);
+                max[axis] = max[axis].max(points[i][axis]);
+            }
+        }
+        (0..3)
+            .max_by(|&a, &b| (max[a] - min[a]).partial_cmp(&(max[b] - min[b])).unwrap())
+            .unwrap()
+    }
+
+    /// Returns the index (into the slice originally passed to `build`) of the
+    /// closest point to `query`.
+    pub(crate) fn nearest(&self, query: [f32; 3]) -> usize {
+        let mut best_index = 0;
+        let mut best_dist = f32::INFINITY;
+        Self::search(&self.root, query, &mut best_index, &mut best_dist);
+        best_index
+    }
+
+    fn search(node: &Option<Box<Node>>, query: [f32; 3], best_index: &mut usize, best_dist: &mut f32) {
+        let node = match node {
+            Some(node) => node,
+            None => return,
+        };
+
+        let dist = squared_distance(node.point, query);
+        if dist < *best_dist {
+            *best_dist = dist;
+            *best_index = node.index;
+        }
+
+        let plane_offset = query[node.axis] - node.point[node.axis];
+        let (near, far) = if plane_offset < 0f32 {
+            (&node.left, &node.right)
+        } else {
+            (&node.right, &node.left)
+        };
+
+        Self::search(near, query, best_index, best_dist);
+        // Only descend into the far side if its splitting plane could still hold a closer point.
+        if plane_offset * plane_offset < *best_dist {
+            Self::search(far, query, best_index, best_dist);
+        }
+    }
+}
+
+fn squared_distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum()
+}
@@ -1,7 +1,14 @@
+mod indexed_png;
+mod kdtree;
+mod palette_io;
+
 use chashmap::CHashMap;
-use image::ImageError;
+use image::{ImageError, RgbImage};
+use kdtree::KdTree;
+pub use indexed_png::write_indexed_png;
+pub use palette_io::load_palette_file;
 use palette::rgb::FromHexError;
-use palette::Srgb;
+use palette::{IntoColor, Lab, LinSrgb, Srgb};
 use parry3d::math::Point;
 use parry3d::na::Isometry3;
 use parry3d::query::{closest_points, ClosestPoints};
@@ -13,43 +20,113 @@ pub const NORD: [&str; 16] = [
     "#88C0D0", "#81A1C1", "#5E81AC", "#BF616A", "#D08770", "#EBCB8B", "#A3BE8C", "#B48EAD",
 ];
 
+/// Selects which color space `ColorPaletteSpace` builds its hull in and
+/// matches pixels against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Match colors directly in gamma-encoded sRGB, same as the original behaviour.
+    Srgb,
+    /// Match colors in perceptually-uniform CIELAB, using the D65 white point.
+    Lab,
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
+    }
+}
+
+/// Selects how a queried pixel is turned into an output color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Project out-of-gamut colors onto the convex hull surface, leaving
+    /// colors already inside the hull untouched.
+    HullProjection,
+    /// Snap every pixel to the single nearest actual palette color, so the
+    /// output only ever contains the palette's own colors.
+    Quantize,
+}
+
+impl Default for MatchMode {
+    fn default() -> Self {
+        MatchMode::HullProjection
+    }
+}
+
 #[derive(Debug)]
 pub enum TransferError {
     IoError(std::io::Error),
     ImgError(ImageError),
     HexError(FromHexError),
     ConvexHullError,
+    PaletteParseError,
+    PngError(png::EncodingError),
+    EmptyPaletteError,
+    PaletteTooLargeError,
+}
+
+enum Matcher {
+    Hull {
+        colorspace: ConvexPolyhedron,
+        zero_ball: Ball,
+        zero_iso: Isometry3<f32>,
+    },
+    Quantize {
+        palette: Vec<[u8; 3]>,
+        tree: KdTree,
+    },
 }
 
 pub struct ColorPaletteSpace {
-    colorspace: ConvexPolyhedron,
-    zero_ball: Ball,
-    zero_iso: Isometry3<f32>,
+    space: ColorSpace,
+    matcher: Matcher,
     cache: CHashMap<[u8; 3], [u8; 3]>,
 }
 
 impl ColorPaletteSpace {
-    pub fn new(palette: &[&str]) -> Result<ColorPaletteSpace, TransferError> {
-        let mut color_points = Vec::new();
-        // Generate Color Palette from Hex Codes
+    pub fn new(
+        palette: &[&str],
+        space: ColorSpace,
+        mode: MatchMode,
+    ) -> Result<ColorPaletteSpace, TransferError> {
+        // Parse every palette hex once into both its rgb bytes and its query-space point
+        let mut points = Vec::with_capacity(palette.len());
+        let mut rgb_bytes = Vec::with_capacity(palette.len());
         for c in palette {
-            let c = Srgb::from_str(c).map_err(TransferError::HexError)?;
-            color_points.push(Point::new(c.red as f32, c.green as f32, c.blue as f32))
+            let srgb = Srgb::from_str(c).map_err(TransferError::HexError)?;
+            rgb_bytes.push([srgb.red, srgb.green, srgb.blue]);
+            points.push(match space {
+                ColorSpace::Srgb => Point::new(srgb.red as f32, srgb.green as f32, srgb.blue as f32),
+                ColorSpace::Lab => lab_from_srgb(srgb.into_format()),
+            });
+        }
+        if points.is_empty() {
+            return Err(TransferError::EmptyPaletteError);
         }
-        // Build Convex Hull around color palette
-        let colorspace = ConvexPolyhedron::from_convex_hull(&color_points)
-            .ok_or(TransferError::ConvexHullError)?;
 
-        // Initialise helper structs
-        let zero_ball = Ball::new(0f32);
-        let zero_iso = Isometry3::translation(0f32, 0f32, 0f32);
-        let cache = CHashMap::new();
+        let matcher = match mode {
+            MatchMode::HullProjection => {
+                let colorspace = ConvexPolyhedron::from_convex_hull(&points)
+                    .ok_or(TransferError::ConvexHullError)?;
+                Matcher::Hull {
+                    colorspace,
+                    zero_ball: Ball::new(0f32),
+                    zero_iso: Isometry3::translation(0f32, 0f32, 0f32),
+                }
+            }
+            MatchMode::Quantize => {
+                let tree_points: Vec<[f32; 3]> = points.iter().map(|p| [p.x, p.y, p.z]).collect();
+                Matcher::Quantize {
+                    palette: rgb_bytes,
+                    tree: KdTree::build(&tree_points),
+                }
+            }
+        };
 
         Ok(ColorPaletteSpace {
-            colorspace,
-            zero_ball,
-            zero_iso,
-            cache,
+            space,
+            matcher,
+            cache: CHashMap::new(),
         })
     }
 
@@ -58,25 +135,137 @@ impl ColorPaletteSpace {
         if let Some(rgb) = self.cache.get(rgb) {
             return *rgb;
         }
-        // Use translation with zero radius sphere as our color point
-        let point = Isometry3::translation(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
-        // Determine closest point of our color space hull to our color point
-        let new = match closest_points(
-            &self.zero_iso,
-            &self.colorspace,
-            &point,
-            &self.zero_ball,
-            99999f32,
-        )
-        .expect("Compatible Shapes")
-        {
-            ClosestPoints::Intersecting => *rgb,
-            ClosestPoints::WithinMargin(new, _) => new.coords.data.0[0].map(|i| i as u8),
-            ClosestPoints::Disjoint => panic!(),
+        // Project the incoming pixel into the same space the palette was built in
+        let query = match self.space {
+            ColorSpace::Srgb => Point::new(rgb[0] as f32, rgb[1] as f32, rgb[2] as f32),
+            ColorSpace::Lab => rgb_to_lab_point(rgb),
+        };
+
+        let new = match &self.matcher {
+            Matcher::Hull {
+                colorspace,
+                zero_ball,
+                zero_iso,
+            } => {
+                let point = Isometry3::translation(query.x, query.y, query.z);
+                // Determine closest point of our color space hull to our color point
+                match closest_points(zero_iso, colorspace, &point, zero_ball, 99999f32)
+                    .expect("Compatible Shapes")
+                {
+                    ClosestPoints::Intersecting => *rgb,
+                    ClosestPoints::WithinMargin(new, _) => match self.space {
+                        ColorSpace::Srgb => new.coords.data.0[0].map(|i| i as u8),
+                        ColorSpace::Lab => lab_point_to_rgb(new),
+                    },
+                    ClosestPoints::Disjoint => panic!(),
+                }
+            }
+            Matcher::Quantize { palette, tree } => {
+                palette[tree.nearest([query.x, query.y, query.z])]
+            }
         };
 
         // Insert new color into cache
         self.cache.insert(*rgb, new);
         new
     }
+
+    /// Quantizes `img` with Floyd-Steinberg error diffusion instead of a flat
+    /// per-pixel lookup. Processes pixels in scan order, carrying the
+    /// quantization error of each pixel forward into its not-yet-processed
+    /// neighbours, so banding in flat regions turns into dithered noise.
+    ///
+    /// Error diffusion depends on each pixel's already-diffused neighbours,
+    /// so this runs as a single serial pass rather than in parallel; each
+    /// pixel's lookup still goes through the cached `get_color`.
+    pub fn dither(&self, img: &RgbImage) -> Vec<u8> {
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+
+        // f32 working buffer, seeded with the original pixel values
+        let mut working: Vec<[f32; 3]> = img
+            .pixels()
+            .map(|p| [p.0[0] as f32, p.0[1] as f32, p.0[2] as f32])
+            .collect();
+        let mut out = vec![0u8; width * height * 3];
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let old = working[index];
+                let old_rgb = [
+                    old[0].round().clamp(0f32, 255f32) as u8,
+                    old[1].round().clamp(0f32, 255f32) as u8,
+                    old[2].round().clamp(0f32, 255f32) as u8,
+                ];
+                let chosen = self.get_color(&old_rgb);
+                out[index * 3] = chosen[0];
+                out[index * 3 + 1] = chosen[1];
+                out[index * 3 + 2] = chosen[2];
+
+                let error = [
+                    old[0] - chosen[0] as f32,
+                    old[1] - chosen[1] as f32,
+                    old[2] - chosen[2] as f32,
+                ];
+
+                let mut diffuse = |dx: isize, dy: isize, weight: f32| {
+                    let nx = x as isize + dx;
+                    let ny = y as isize + dy;
+                    if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                        return;
+                    }
+                    let neighbour = ny as usize * width + nx as usize;
+                    for channel in 0..3 {
+                        working[neighbour][channel] += error[channel] * weight;
+                    }
+                };
+
+                diffuse(1, 0, 7f32 / 16f32);
+                diffuse(-1, 1, 3f32 / 16f32);
+                diffuse(0, 1, 5f32 / 16f32);
+                diffuse(1, 1, 1f32 / 16f32);
+            }
+        }
+
+        out
+    }
+
+    /// Returns the palette colors backing `MatchMode::Quantize`, or `None`
+    /// when this space was built with `MatchMode::HullProjection`.
+    pub fn palette_colors(&self) -> Option<&[[u8; 3]]> {
+        match &self.matcher {
+            Matcher::Quantize { palette, .. } => Some(palette),
+            Matcher::Hull { .. } => None,
+        }
+    }
+}
+
+/// Converts an sRGB color already in [0,1] float form into the CIELAB `Point`
+/// used to query the hull (sRGB -> linear -> XYZ -> Lab).
+fn lab_from_srgb(srgb: Srgb<f32>) -> Point<f32> {
+    let linear: LinSrgb = srgb.into_linear();
+    let lab: Lab = linear.into_color();
+    Point::new(lab.l, lab.a, lab.b)
+}
+
+/// Converts an 8-bit pixel into the CIELAB `Point` used to query the hull.
+fn rgb_to_lab_point(rgb: &[u8; 3]) -> Point<f32> {
+    lab_from_srgb(Srgb::new(rgb[0], rgb[1], rgb[2]).into_format())
+}
+
+/// Converts a CIELAB hull point back to a clamped 8-bit sRGB pixel.
+fn lab_point_to_rgb(point: Point<f32>) -> [u8; 3] {
+    let lab = Lab::new(point.x, point.y, point.z);
+    let linear: LinSrgb = lab.into_color();
+    let srgb = Srgb::from_linear(linear);
+    [
+        clamp_channel(srgb.red),
+        clamp_channel(srgb.green),
+        clamp_channel(srgb.blue),
+    ]
+}
+
+fn clamp_channel(c: f32) -> u8 {
+    (c * 255f32).round().clamp(0f32, 255f32) as u8
 }
@@ -1,4 +1,4 @@
-use argparse::{ArgumentParser, Store};
+use argparse::{ArgumentParser, Store, StoreTrue};
 use atomic_counter::AtomicCounter;
 use atomic_counter::RelaxedCounter;
 use image::io::Reader as ImageReader;
@@ -6,6 +6,7 @@ use image::save_buffer_with_format;
 use image::ImageFormat;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use std::thread;
 use std::thread::sleep;
@@ -16,7 +17,11 @@ use color_palatte_transfer::*;
 fn main() -> Result<(), TransferError> {
     let mut output = String::from("");
     let mut colors = String::from("");
+    let mut colors_file = String::from("");
     let mut image = String::from("");
+    let mut perceptual = false;
+    let mut mode = String::from("hull");
+    let mut dither = false;
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Converts image to color palette");
@@ -34,14 +39,43 @@ fn main() -> Result<(), TransferError> {
             Example: \"2E3440,3B4252,434C5E\". \
             Uses Nord color palette if not set: https://www.nordtheme.com/",
         );
+        ap.refer(&mut colors_file).add_option(
+            &["--colors-file"],
+            Store,
+            "Load the palette from a file instead of --colors. \
+            Supports GIMP .gpl palettes, a newline- or comma-delimited hex list, \
+            and a JSON array of hex strings.",
+        );
+        ap.refer(&mut perceptual).add_option(
+            &["-p", "--perceptual"],
+            StoreTrue,
+            "Match colors in perceptual CIELAB space instead of raw sRGB. \
+            Produces more natural-looking results at the cost of extra conversions.",
+        );
+        ap.refer(&mut mode).add_option(
+            &["-m", "--mode"],
+            Store,
+            "Matching mode: \"hull\" projects out-of-gamut colors onto the palette's convex \
+            hull (default), \"quantize\" snaps every pixel to the nearest actual palette color.",
+        );
+        ap.refer(&mut dither).add_option(
+            &["-d", "--dither"],
+            StoreTrue,
+            "Apply Floyd-Steinberg error diffusion while quantizing, instead of a flat \
+            per-pixel lookup. Only has an effect combined with --mode quantize.",
+        );
         ap.refer(&mut image)
             .add_argument("image", Store, "Image to convert")
             .required();
         ap.parse_args_or_exit();
     }
 
-    // Use either Nord or set color palette
-    let colors: Vec<_> = if colors.is_empty() {
+    // Use a palette file, an explicit --colors list, or fall back to Nord
+    let loaded_colors;
+    let colors: Vec<_> = if !colors_file.is_empty() {
+        loaded_colors = load_palette_file(&colors_file)?;
+        loaded_colors.iter().map(String::as_str).collect()
+    } else if colors.is_empty() {
         NORD.to_vec()
     } else {
         colors.split(',').collect()
@@ -49,7 +83,16 @@ fn main() -> Result<(), TransferError> {
 
     // Generate Color Palette based on
     println!("[1/4] Generating Color Space");
-    let palette = ColorPaletteSpace::new(colors.as_slice())?;
+    let space = if perceptual {
+        ColorSpace::Lab
+    } else {
+        ColorSpace::Srgb
+    };
+    let mode = match mode.as_str() {
+        "quantize" => MatchMode::Quantize,
+        _ => MatchMode::HullProjection,
+    };
+    let palette = ColorPaletteSpace::new(colors.as_slice(), space, mode)?;
 
     // Open Image
     println!("[2/4] Open Image");
@@ -60,38 +103,44 @@ fn main() -> Result<(), TransferError> {
     let img = img.decode().map_err(TransferError::ImgError)?.to_rgb8();
     let dim = img.dimensions();
 
-    let counter = Arc::new(RelaxedCounter::new(0));
-    let counter2 = counter.clone();
-    let num_pixel = dim.0 * dim.1;
     println!("[3/4] Calculating Pixel");
-    let pb = Arc::new(RwLock::new(ProgressBar::new(num_pixel as u64)));
-    pb.write().unwrap().set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {wide_bar} {pos:>7}/{len:7}")
-            .progress_chars("##-"),
-    );
-    let local_pb = pb.clone();
-    thread::spawn(move || loop {
-        let num = counter2.get();
-        pb.write().unwrap().set_position(num as u64);
-        if num >= num_pixel as usize {
-            pb.write().unwrap().finish_and_clear();
-            return;
-        }
-        sleep(Duration::from_millis(12));
-    });
+    let bytes: Vec<u8> = if dither && mode == MatchMode::Quantize {
+        // Error diffusion carries state between pixels, so it runs as a single serial pass.
+        palette.dither(&img)
+    } else {
+        let counter = Arc::new(RelaxedCounter::new(0));
+        let counter2 = counter.clone();
+        let num_pixel = dim.0 * dim.1;
+        let pb = Arc::new(RwLock::new(ProgressBar::new(num_pixel as u64)));
+        pb.write().unwrap().set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {wide_bar} {pos:>7}/{len:7}")
+                .progress_chars("##-"),
+        );
+        let local_pb = pb.clone();
+        thread::spawn(move || loop {
+            let num = counter2.get();
+            pb.write().unwrap().set_position(num as u64);
+            if num >= num_pixel as usize {
+                pb.write().unwrap().finish_and_clear();
+                return;
+            }
+            sleep(Duration::from_millis(12));
+        });
 
-    let mut pixel: Vec<_> = img.pixels().cloned().collect();
-    // Apply new colors in parallel
-    let bytes: Vec<u8> = pixel
-        .par_drain(..)
-        .flat_map_iter(|rgb| {
-            let rgb = palette.get_color(&rgb.0);
-            counter.inc();
-            rgb
-        })
-        .collect();
-    local_pb.write().unwrap().finish_and_clear();
+        let mut pixel: Vec<_> = img.pixels().cloned().collect();
+        // Apply new colors in parallel
+        let bytes = pixel
+            .par_drain(..)
+            .flat_map_iter(|rgb| {
+                let rgb = palette.get_color(&rgb.0);
+                counter.inc();
+                rgb
+            })
+            .collect();
+        local_pb.write().unwrap().finish_and_clear();
+        bytes
+    };
 
     // Determine output name
     let output = if output.is_empty() {
@@ -103,10 +152,30 @@ fn main() -> Result<(), TransferError> {
     } else {
         output
     };
-    // Write to file
+    // Write to file. A quantized image only ever contains the palette's own
+    // colors, so for PNG output it's much smaller written as indexed-color
+    // with the palette embedded instead of full RGB8.
     println!("[4/4] Write Image");
-    save_buffer_with_format(output, &bytes, dim.0, dim.1, image::ColorType::Rgb8, format)
-        .map_err(TransferError::ImgError)?;
+    if let (MatchMode::Quantize, ImageFormat::Png, Some(palette_colors)) =
+        (mode, format, palette.palette_colors())
+    {
+        if palette_colors.len() > 256 {
+            return Err(TransferError::PaletteTooLargeError);
+        }
+        let index_of: HashMap<[u8; 3], u8> = palette_colors
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (c, i as u8))
+            .collect();
+        let indices: Vec<u8> = bytes
+            .chunks_exact(3)
+            .map(|p| index_of[&[p[0], p[1], p[2]]])
+            .collect();
+        write_indexed_png(output, dim.0, dim.1, &indices, palette_colors)?;
+    } else {
+        save_buffer_with_format(output, &bytes, dim.0, dim.1, image::ColorType::Rgb8, format)
+            .map_err(TransferError::ImgError)?;
+    }
 
     Ok(())
 }
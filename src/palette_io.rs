@@ -0,0 +1,82 @@
+//! Loading palettes from external files: GIMP `.gpl`, hex lists, and JSON.
+
+use crate::TransferError;
+use std::fs;
+use std::path::Path;
+
+/// Loads a palette file and returns its colors as `#RRGGBB` hex strings,
+/// ready to be borrowed into the `&[&str]` form `ColorPaletteSpace::new`
+/// expects.
+///
+/// Supports GIMP `.gpl` palettes, a newline- or comma-delimited list of hex
+/// codes, and a JSON array of hex strings. The format is picked from the
+/// file extension, falling back to sniffing the content.
+pub fn load_palette_file(path: impl AsRef<Path>) -> Result<Vec<String>, TransferError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(TransferError::IoError)?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gpl") => parse_gpl(&content),
+        Some("json") => parse_json(&content),
+        _ if content.trim_start().starts_with("GIMP Palette") => parse_gpl(&content),
+        _ if content.trim_start().starts_with('[') => parse_json(&content),
+        _ => parse_hex_list(&content),
+    }
+}
+
+/// Parses a GIMP `.gpl` palette: skips the `GIMP Palette` header and
+/// `Name:`/`Columns:` lines, and reads each remaining line's first three
+/// whitespace-separated integers as R G B, ignoring any trailing color name.
+fn parse_gpl(content: &str) -> Result<Vec<String>, TransferError> {
+    let mut colors = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty()
+            || line.starts_with('#')
+            || line == "GIMP Palette"
+            || line.starts_with("Name:")
+            || line.starts_with("Columns:")
+        {
+            continue;
+        }
+
+        let mut channels = line.split_whitespace();
+        let r = next_channel(&mut channels)?;
+        let g = next_channel(&mut channels)?;
+        let b = next_channel(&mut channels)?;
+        colors.push(format!("#{:02X}{:02X}{:02X}", r, g, b));
+    }
+    Ok(colors)
+}
+
+fn next_channel<'a>(channels: &mut impl Iterator<Item = &'a str>) -> Result<u8, TransferError> {
+    channels
+        .next()
+        .and_then(|v| v.parse().ok())
+        .ok_or(TransferError::PaletteParseError)
+}
+
+/// Parses a newline- or comma-delimited list of `#RRGGBB` hex codes.
+fn parse_hex_list(content: &str) -> Result<Vec<String>, TransferError> {
+    content
+        .split(|c: char| c == ',' || c == '\n' || c == '\r')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(normalize_hex)
+        .collect()
+}
+
+/// Parses a JSON array of hex strings, e.g. `["#2E3440", "#3B4252"]`.
+fn parse_json(content: &str) -> Result<Vec<String>, TransferError> {
+    let raw: Vec<String> =
+        serde_json::from_str(content).map_err(|_| TransferError::PaletteParseError)?;
+    raw.iter().map(|s| normalize_hex(s)).collect()
+}
+
+fn normalize_hex(s: &str) -> Result<String, TransferError> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(TransferError::PaletteParseError);
+    }
+    Ok(format!("#{}", hex.to_uppercase()))
+}